@@ -0,0 +1,78 @@
+// Copyright 2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! Streaming digest support for end-to-end transfer verification.
+
+use sha2::Digest as _;
+
+use crate::{Error, Result};
+
+/// An in-progress digest. Bytes are fed in as they're written/sent so
+/// verifying a transfer needs no second pass over the bytes it moves; a
+/// resumed download re-reads only the bytes already on disk (see
+/// `prime_from_file`).
+pub(crate) enum Checksum {
+    Sha256(sha2::Sha256),
+    Blake3(blake3::Hasher),
+}
+
+impl Checksum {
+    /// Builds an empty hasher for `algorithm` ("sha256" or "blake3").
+    pub(crate) fn new(algorithm: &str) -> Result<Self> {
+        match algorithm {
+            "sha256" => Ok(Self::Sha256(sha2::Sha256::new())),
+            "blake3" => Ok(Self::Blake3(blake3::Hasher::new())),
+            other => Err(Error::Checksum(format!(
+                "unsupported checksum algorithm: {other}"
+            ))),
+        }
+    }
+
+    /// Parses a `"<algorithm>:<hex digest>"` string as given to `download`,
+    /// returning the (empty) hasher and the lowercased expected digest.
+    pub(crate) fn parse(checksum: &str) -> Result<(Self, String)> {
+        let (algorithm, expected) = checksum.split_once(':').ok_or_else(|| {
+            Error::Checksum(format!(
+                "expected checksum in \"<algorithm>:<hex>\" form, got \"{checksum}\""
+            ))
+        })?;
+        Ok((Self::new(algorithm)?, expected.to_ascii_lowercase()))
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(bytes),
+            Self::Blake3(hasher) => {
+                hasher.update(bytes);
+            }
+        }
+    }
+
+    /// Finalizes a clone of the current state, so the hasher can keep
+    /// accumulating bytes (or be finalized again) afterwards.
+    pub(crate) fn finalize_hex(&self) -> String {
+        match self {
+            Self::Sha256(hasher) => hex::encode(hasher.clone().finalize()),
+            Self::Blake3(hasher) => hasher.clone().finalize().to_hex().to_string(),
+        }
+    }
+
+    /// Feeds the hasher with the bytes already on disk at `path`, used to
+    /// prime a resumed download's digest with the bytes written before the
+    /// current attempt started.
+    pub(crate) async fn prime_from_file(&mut self, path: &str) -> Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut buf = [0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            self.update(&buf[..n]);
+        }
+        Ok(())
+    }
+}
@@ -6,18 +6,34 @@ use futures_util::TryStreamExt;
 use serde::{ser::Serializer, Serialize};
 use tauri::{
     command,
+    ipc::Channel,
     plugin::{Builder as PluginBuilder, TauriPlugin},
-    Runtime, Window,
+    Manager, Runtime, State, Window,
 };
 use tokio::{
-    fs::File,
-    io::{AsyncWriteExt, BufWriter},
+    fs::{File, OpenOptions},
+    io::{AsyncRead, AsyncWriteExt, BufWriter},
+    time::sleep,
+};
+use tokio_util::{
+    codec::{BytesCodec, FramedRead},
+    sync::CancellationToken,
 };
-use tokio_util::codec::{BytesCodec, FramedRead};
 
 use read_progress_stream::ReadProgressStream;
 
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+mod checksum;
+mod s3;
+
+use checksum::Checksum;
+pub use s3::S3Config;
 
 type Result<T> = std::result::Result<T, Error>;
 
@@ -29,6 +45,16 @@ pub enum Error {
     Request(#[from] reqwest::Error),
     #[error("{0}")]
     ContentLength(String),
+    #[error("{0}")]
+    Http(String),
+    #[error("transfer was cancelled")]
+    Cancelled,
+    #[error("S3 error: {0}")]
+    S3(String),
+    #[error("{0}")]
+    Checksum(String),
+    #[error("checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
 }
 
 impl Serialize for Error {
@@ -40,87 +66,306 @@ impl Serialize for Error {
     }
 }
 
+impl Error {
+    /// Whether this error is worth retrying: any I/O or `reqwest` error is
+    /// (including ones raised before any bytes were transferred, such as a
+    /// malformed URL or a file that can't be created); HTTP status, checksum
+    /// and cancellation errors are not.
+    fn is_retryable(&self) -> bool {
+        matches!(self, Error::Io(_) | Error::Request(_))
+    }
+}
+
 #[derive(Clone, Serialize)]
-struct ProgressPayload {
+pub(crate) struct ProgressPayload {
+    pub(crate) id: u32,
+    pub(crate) progress: u64,
+    pub(crate) total: u64,
+    pub(crate) bytes_per_second: u64,
+    pub(crate) eta_seconds: Option<u64>,
+}
+
+/// Builds a progress update from the bytes sent in the last emit window,
+/// deriving throughput and a naive remaining-time estimate from it.
+pub(crate) fn progress_payload(
     id: u32,
-    progress: u64,
+    window_progress: u64,
+    sent: u64,
     total: u64,
+    elapsed: Duration,
+) -> ProgressPayload {
+    let bytes_per_second = (window_progress as f64 / elapsed.as_secs_f64()).round() as u64;
+    let eta_seconds = if bytes_per_second > 0 && total > sent {
+        Some((total - sent) / bytes_per_second)
+    } else {
+        None
+    };
+    ProgressPayload {
+        id,
+        progress: window_progress,
+        total,
+        bytes_per_second,
+        eta_seconds,
+    }
 }
 
-#[command]
-async fn download<R: Runtime>(
-    window: Window<R>,
+#[derive(Clone, Serialize)]
+struct RetryPayload {
+    id: u32,
+    attempt: u32,
+    delay_ms: u64,
+    error: String,
+}
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// Tracks the in-flight transfers so `cancel_download`/`cancel_upload` can
+/// reach them by the caller-supplied id.
+#[derive(Default)]
+pub(crate) struct TransferState {
+    cancellation_tokens: Mutex<HashMap<u32, CancellationToken>>,
+}
+
+impl TransferState {
+    fn register(&self, id: u32) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.cancellation_tokens
+            .lock()
+            .unwrap()
+            .insert(id, token.clone());
+        token
+    }
+
+    fn unregister(&self, id: u32) {
+        self.cancellation_tokens.lock().unwrap().remove(&id);
+    }
+
+    fn cancel(&self, id: u32) -> bool {
+        match self.cancellation_tokens.lock().unwrap().remove(&id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Streams `url` into `tmp_path`, resuming from the file's current length
+/// (if any) via a `Range` request, and returns once the body has been fully
+/// written and flushed. Does not rename `tmp_path` to its final destination;
+/// callers retry this on transient errors before doing so.
+async fn download_to_tmp(
+    client: &reqwest::Client,
+    on_progress: &Channel<ProgressPayload>,
     id: u32,
     url: &str,
-    file_path: &str,
-    headers: HashMap<String, String>,
-) -> Result<u32> {
-    let client = reqwest::Client::new();
+    tmp_path: &str,
+    headers: &HashMap<String, String>,
+    token: &CancellationToken,
+    delete_partial_on_cancel: bool,
+    checksum: Option<&str>,
+) -> Result<()> {
+    let existing_len = match tokio::fs::metadata(tmp_path).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+
+    let mut checksum = checksum.map(Checksum::parse).transpose()?;
 
     let mut request = client.get(url);
-    // Loop trought the headers keys and values
-    // and add them to the request object.
     for (key, value) in headers {
-        request = request.header(&key, value);
+        request = request.header(key, value);
+    }
+    if existing_len > 0 {
+        request = request.header("Range", format!("bytes={existing_len}-"));
     }
 
     let response = request.send().await?;
-    let total = response.content_length().unwrap_or(0);
+    let status = response.status();
+    let resuming = existing_len > 0 && status.as_u16() == 206;
 
-    let mut file = BufWriter::new(File::create(file_path).await?);
+    if !resuming && !status.is_success() {
+        return Err(Error::Http(format!("GET {url} failed with status {status}")));
+    }
+
+    let (mut file, mut written) = if resuming {
+        // Only prime the hasher from the bytes already on disk once we know
+        // the server actually honored our Range request - if it falls back
+        // to a full restart below, those stale bytes get overwritten and
+        // must not be folded into the digest.
+        if let Some((hasher, _)) = &mut checksum {
+            hasher.prime_from_file(tmp_path).await?;
+        }
+        (
+            BufWriter::new(OpenOptions::new().append(true).open(tmp_path).await?),
+            existing_len,
+        )
+    } else {
+        // Server doesn't support ranges (or there was nothing to resume) -
+        // start over from scratch.
+        let file = BufWriter::new(File::create(tmp_path).await?);
+        (file, 0)
+    };
+
+    let total = response.content_length().unwrap_or(0) + written;
     let mut stream = response.bytes_stream();
 
     let mut last_emit_time = Instant::now();
     let mut temp_progress = 0;
 
-    while let Some(chunk) = stream.try_next().await? {
+    loop {
+        let chunk = tokio::select! {
+            biased;
+            _ = token.cancelled() => {
+                file.flush().await?;
+                if delete_partial_on_cancel {
+                    drop(file);
+                    let _ = tokio::fs::remove_file(tmp_path).await;
+                }
+                return Err(Error::Cancelled);
+            }
+            chunk = stream.try_next() => chunk?,
+        };
+        let Some(chunk) = chunk else {
+            break;
+        };
+
         file.write_all(&chunk).await?;
+        if let Some((hasher, _)) = &mut checksum {
+            hasher.update(&chunk);
+        }
+        written += chunk.len() as u64;
         temp_progress += chunk.len() as u64;
         let elapsed = last_emit_time.elapsed();
         if elapsed >= Duration::from_secs(1) {
-            window
-                .emit(
-                    "download://progress",
-                    ProgressPayload {
-                        id,
-                        progress: temp_progress as u64,
-                        total,
-                    },
-                );
+            let _ = on_progress.send(progress_payload(id, temp_progress, written, total, elapsed));
             last_emit_time = Instant::now();
             temp_progress = 0;
         }
     }
     if temp_progress != 0 {
-        window
-            .emit(
-                "download://progress",
-                ProgressPayload {
-                    id,
-                    progress: temp_progress as u64,
-                    total,
-                },
-            );
+        let _ = on_progress.send(progress_payload(
+            id,
+            temp_progress,
+            written,
+            total,
+            last_emit_time.elapsed(),
+        ));
     }
     file.flush().await?;
 
-    Ok(id)
+    if let Some((hasher, expected)) = checksum {
+        let actual = hasher.finalize_hex();
+        if actual != expected {
+            drop(file);
+            let _ = tokio::fs::remove_file(tmp_path).await;
+            return Err(Error::ChecksumMismatch { expected, actual });
+        }
+    }
+
+    Ok(())
 }
 
 #[command]
-async fn upload<R: Runtime>(
+pub(crate) async fn download<R: Runtime>(
     window: Window<R>,
+    state: State<'_, TransferState>,
     id: u32,
     url: &str,
     file_path: &str,
     headers: HashMap<String, String>,
+    max_retries: Option<u32>,
+    delete_partial_on_cancel: Option<bool>,
+    checksum: Option<String>,
+    on_progress: Channel<ProgressPayload>,
+) -> Result<u32> {
+    let max_retries = max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+    let delete_partial_on_cancel = delete_partial_on_cancel.unwrap_or(false);
+    let tmp_path = format!("{file_path}.tmp");
+    let client = reqwest::Client::new();
+    let token = state.register(id);
+
+    let mut attempt = 0;
+    let mut delay = INITIAL_RETRY_DELAY;
+
+    let result = loop {
+        match download_to_tmp(
+            &client,
+            &on_progress,
+            id,
+            url,
+            &tmp_path,
+            &headers,
+            &token,
+            delete_partial_on_cancel,
+            checksum.as_deref(),
+        )
+        .await
+        {
+            Ok(()) => break Ok(()),
+            Err(err) if err.is_retryable() && attempt < max_retries => {
+                attempt += 1;
+                let _ = window.emit(
+                    "download://retry",
+                    RetryPayload {
+                        id,
+                        attempt,
+                        delay_ms: delay.as_millis() as u64,
+                        error: err.to_string(),
+                    },
+                );
+                tokio::select! {
+                    biased;
+                    _ = token.cancelled() => break Err(Error::Cancelled),
+                    _ = sleep(delay) => {}
+                }
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+            Err(err) => break Err(err),
+        }
+    };
+
+    state.unregister(id);
+    result?;
+
+    tokio::fs::rename(&tmp_path, file_path).await?;
+
+    Ok(id)
+}
+
+#[command]
+async fn cancel_download(state: State<'_, TransferState>, id: u32) -> Result<bool> {
+    Ok(state.cancel(id))
+}
+
+#[command]
+async fn upload(
+    state: State<'_, TransferState>,
+    id: u32,
+    url: &str,
+    file_path: &str,
+    headers: HashMap<String, String>,
+    digest_algorithm: Option<String>,
+    on_progress: Channel<ProgressPayload>,
 ) -> Result<serde_json::Value> {
     // Read the file
     let file = File::open(file_path).await?;
+    let token = state.register(id);
+
+    let hasher = digest_algorithm
+        .as_deref()
+        .map(Checksum::new)
+        .transpose()?
+        .map(|hasher| Arc::new(Mutex::new(hasher)));
 
     // Create the request and attach the file to the body
     let client = reqwest::Client::new();
-    let mut request = client.put(url).body(file_to_body(id, window, file));
+    let mut request = client
+        .put(url)
+        .body(file_to_body(id, on_progress, file, hasher.clone()));
 
     // Loop trought the headers keys and values
     // and add them to the request object.
@@ -128,32 +373,120 @@ async fn upload<R: Runtime>(
         request = request.header(&key, value);
     }
 
-    let response = request.send().await?;
+    let sent = tokio::select! {
+        biased;
+        _ = token.cancelled() => Err(Error::Cancelled),
+        response = request.send() => response.map_err(Error::from),
+    };
+
+    state.unregister(id);
+    let response = sent?;
+    let body: serde_json::Value = response.json().await?;
+
+    match (hasher, digest_algorithm) {
+        (Some(hasher), Some(algorithm)) => {
+            let digest = hasher.lock().unwrap().finalize_hex();
+            Ok(serde_json::json!({
+                "response": body,
+                "digest": format!("{algorithm}:{digest}"),
+            }))
+        }
+        _ => Ok(body),
+    }
+}
+
+#[command]
+async fn cancel_upload(state: State<'_, TransferState>, id: u32) -> Result<bool> {
+    Ok(state.cancel(id))
+}
+
+/// Like `upload`, but sends the file as a `multipart/form-data` body
+/// instead of a raw PUT, for servers that expect a web-style form upload.
+#[command]
+async fn upload_multipart(
+    state: State<'_, TransferState>,
+    id: u32,
+    url: &str,
+    file_path: &str,
+    headers: HashMap<String, String>,
+    field_name: Option<String>,
+    file_name: Option<String>,
+    mime_type: Option<String>,
+    fields: Option<HashMap<String, String>>,
+    method: Option<String>,
+    on_progress: Channel<ProgressPayload>,
+) -> Result<serde_json::Value> {
+    let field_name = field_name.unwrap_or_else(|| "file".to_string());
+    let file_name = file_name.unwrap_or_else(|| {
+        Path::new(file_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    });
+
+    let file = File::open(file_path).await?;
+    let token = state.register(id);
+
+    let mut part = reqwest::multipart::Part::stream(file_to_body(id, on_progress, file, None))
+        .file_name(file_name);
+    if let Some(mime_type) = mime_type {
+        part = part.mime_str(&mime_type)?;
+    }
+
+    let mut form = reqwest::multipart::Form::new().part(field_name, part);
+    for (key, value) in fields.unwrap_or_default() {
+        form = form.text(key, value);
+    }
+
+    let client = reqwest::Client::new();
+    let mut request = match method.as_deref().map(str::to_ascii_uppercase).as_deref() {
+        Some("PUT") => client.put(url),
+        _ => client.post(url),
+    }
+    .multipart(form);
+
+    for (key, value) in headers {
+        request = request.header(&key, value);
+    }
+
+    let sent = tokio::select! {
+        biased;
+        _ = token.cancelled() => Err(Error::Cancelled),
+        response = request.send() => response.map_err(Error::from),
+    };
+
+    state.unregister(id);
+    let response = sent?;
 
     response.json().await.map_err(Into::into)
 }
 
-fn file_to_body<R: Runtime>(id: u32, window: Window<R>, file: File) -> reqwest::Body {
-    let stream = FramedRead::new(file, BytesCodec::new()).map_ok(|r| r.freeze());
-    let window = Mutex::new(window);
+pub(crate) fn file_to_body(
+    id: u32,
+    on_progress: Channel<ProgressPayload>,
+    reader: impl AsyncRead + Unpin + Send + 'static,
+    hasher: Option<Arc<Mutex<Checksum>>>,
+) -> reqwest::Body {
+    let stream = FramedRead::new(reader, BytesCodec::new()).map_ok(move |r| {
+        let bytes = r.freeze();
+        if let Some(hasher) = &hasher {
+            hasher.lock().unwrap().update(&bytes);
+        }
+        bytes
+    });
 
     let mut temp_progress = 0;
+    let mut sent = 0;
     let mut last_emit_time = Instant::now();
 
     reqwest::Body::wrap_stream(ReadProgressStream::new(
         stream,
         Box::new(move |progress, total| {
-            temp_progress = temp_progress + progress;
+            temp_progress += progress;
+            sent += progress;
             let elapsed = last_emit_time.elapsed();
             if elapsed >= Duration::from_secs(1) {
-                let _ = window.lock().unwrap().emit(
-                    "upload://progress",
-                    ProgressPayload {
-                        id,
-                        progress,
-                        total,
-                    },
-                );
+                let _ = on_progress.send(progress_payload(id, temp_progress, sent, total, elapsed));
                 temp_progress = 0;
                 last_emit_time = Instant::now();
             }
@@ -161,8 +494,155 @@ fn file_to_body<R: Runtime>(id: u32, window: Window<R>, file: File) -> reqwest::
     ))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn noop_progress() -> Channel<ProgressPayload> {
+        Channel::new(|_body| Ok(()))
+    }
+
+    fn tmp_path() -> String {
+        std::env::temp_dir()
+            .join(format!("upload-plugin-test-{}.tmp", uuid_like()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // No uuid dependency in this crate - a process-unique-enough suffix is
+    // all a throwaway test file needs.
+    fn uuid_like() -> u64 {
+        static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    }
+
+    #[tokio::test]
+    async fn resumes_with_range_header_on_206() {
+        let server = MockServer::start().await;
+        let tmp = tmp_path();
+        tokio::fs::write(&tmp, b"hello ").await.unwrap();
+
+        Mock::given(method("GET"))
+            .and(path("/file"))
+            .and(header("Range", "bytes=6-"))
+            .respond_with(ResponseTemplate::new(206).set_body_bytes(b"world".to_vec()))
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::new();
+        let token = CancellationToken::new();
+        download_to_tmp(
+            &client,
+            &noop_progress(),
+            1,
+            &format!("{}/file", server.uri()),
+            &tmp,
+            &HashMap::new(),
+            &token,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(tokio::fs::read(&tmp).await.unwrap(), b"hello world");
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+
+    #[tokio::test]
+    async fn non_success_status_is_not_written_to_disk() {
+        let server = MockServer::start().await;
+        let tmp = tmp_path();
+        tokio::fs::write(&tmp, b"hello ").await.unwrap();
+
+        Mock::given(method("GET"))
+            .and(path("/file"))
+            .respond_with(ResponseTemplate::new(403).set_body_bytes(b"forbidden".to_vec()))
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::new();
+        let token = CancellationToken::new();
+        let err = download_to_tmp(
+            &client,
+            &noop_progress(),
+            1,
+            &format!("{}/file", server.uri()),
+            &tmp,
+            &HashMap::new(),
+            &token,
+            false,
+            None,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, Error::Http(_)));
+        // The pre-existing bytes must be untouched - a failed attempt must
+        // not truncate a resumable .tmp file or write the error body to it.
+        assert_eq!(tokio::fs::read(&tmp).await.unwrap(), b"hello ");
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+
+    #[tokio::test]
+    async fn checksum_of_200_fallback_ignores_stale_partial_bytes() {
+        let server = MockServer::start().await;
+        let tmp = tmp_path();
+        // Stale bytes from a previous attempt - not a prefix of the full
+        // content the server is about to send.
+        tokio::fs::write(&tmp, b"stale-partial-bytes").await.unwrap();
+
+        let full_content = b"the complete file content".to_vec();
+        let expected_digest = {
+            use sha2::Digest;
+            hex::encode(sha2::Sha256::digest(&full_content))
+        };
+
+        Mock::given(method("GET"))
+            .and(path("/file"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(full_content.clone()))
+            .mount(&server)
+            .await;
+
+        let client = reqwest::Client::new();
+        let token = CancellationToken::new();
+        download_to_tmp(
+            &client,
+            &noop_progress(),
+            1,
+            &format!("{}/file", server.uri()),
+            &tmp,
+            &HashMap::new(),
+            &token,
+            false,
+            Some(&format!("sha256:{expected_digest}")),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(tokio::fs::read(&tmp).await.unwrap(), full_content);
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+}
+
 pub fn init<R: Runtime>() -> TauriPlugin<R> {
     PluginBuilder::new("upload")
-        .invoke_handler(tauri::generate_handler![download, upload])
+        .invoke_handler(tauri::generate_handler![
+            download,
+            cancel_download,
+            upload,
+            cancel_upload,
+            upload_multipart,
+            s3::download_s3,
+            s3::upload_s3
+        ])
+        .setup(|app, _api| {
+            app.manage(TransferState::default());
+            Ok(())
+        })
         .build()
 }
@@ -0,0 +1,287 @@
+// Copyright 2021 Tauri Programme within The Commons Conservancy
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! Object-storage transfer mode: presigned S3 requests for simple
+//! GET/PUT, and multipart upload for files too large to push in one shot.
+
+use std::{collections::HashMap, time::Duration};
+
+use rusty_s3::{actions::S3Action, Bucket, Credentials, UrlStyle};
+use serde::Deserialize;
+use tauri::{command, ipc::Channel, Runtime, State, Window};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, SeekFrom},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{download, file_to_body, Error, ProgressPayload, Result, TransferState};
+
+/// How long presigned URLs stay valid for. Requests are made immediately
+/// after signing, so this only needs to cover clock skew and retries.
+const PRESIGN_EXPIRY: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_PART_SIZE: u64 = 8 * 1024 * 1024;
+/// S3's hard cap on the number of parts in a single multipart upload.
+/// Raise `part_size` if a file needs more parts than this to fit.
+const MAX_PARTS: u64 = 10_000;
+
+#[derive(Debug, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// Custom endpoint for S3-compatible stores (MinIO, R2, ...). Defaults
+    /// to AWS's regional endpoint when omitted.
+    pub endpoint: Option<String>,
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+    #[serde(default)]
+    pub path_style: bool,
+}
+
+fn bucket(config: &S3Config) -> Result<Bucket> {
+    let endpoint = match &config.endpoint {
+        Some(endpoint) => endpoint.clone(),
+        None => format!("https://s3.{}.amazonaws.com", config.region),
+    };
+    let endpoint = endpoint
+        .parse()
+        .map_err(|err| Error::S3(format!("invalid endpoint: {err}")))?;
+    let url_style = if config.path_style {
+        UrlStyle::Path
+    } else {
+        UrlStyle::VirtualHost
+    };
+    Bucket::new(endpoint, url_style, config.bucket.clone(), config.region.clone())
+        .map_err(|err| Error::S3(err.to_string()))
+}
+
+fn credentials(config: &S3Config) -> Credentials {
+    match &config.session_token {
+        Some(token) => {
+            Credentials::new_with_token(&config.access_key, &config.secret_key, token)
+        }
+        None => Credentials::new(&config.access_key, &config.secret_key),
+    }
+}
+
+#[command]
+pub(crate) async fn download_s3<R: Runtime>(
+    window: Window<R>,
+    state: State<'_, TransferState>,
+    id: u32,
+    config: S3Config,
+    key: &str,
+    file_path: &str,
+    max_retries: Option<u32>,
+    checksum: Option<String>,
+    on_progress: Channel<ProgressPayload>,
+) -> Result<u32> {
+    let bucket = bucket(&config)?;
+    let credentials = credentials(&config);
+    let action = bucket.get_object(Some(&credentials), key);
+    let url = action.sign(PRESIGN_EXPIRY);
+
+    // A presigned GET is just a URL - everything downstream (resume,
+    // retry-with-backoff, checksum verification, cancellation) is already
+    // handled by `download`.
+    download(
+        window,
+        state,
+        id,
+        url.as_str(),
+        file_path,
+        HashMap::new(),
+        max_retries,
+        None,
+        checksum,
+        on_progress,
+    )
+    .await
+}
+
+#[command]
+pub(crate) async fn upload_s3(
+    state: State<'_, TransferState>,
+    id: u32,
+    config: S3Config,
+    key: &str,
+    file_path: &str,
+    part_size: Option<u64>,
+    on_progress: Channel<ProgressPayload>,
+) -> Result<serde_json::Value> {
+    let part_size = part_size.unwrap_or(DEFAULT_PART_SIZE).max(1);
+    let total_len = tokio::fs::metadata(file_path).await?.len();
+    let bucket = bucket(&config)?;
+    let credentials = credentials(&config);
+    let token = state.register(id);
+
+    let result = if total_len <= part_size {
+        upload_single(&on_progress, &bucket, &credentials, id, key, file_path, &token).await
+    } else {
+        upload_multipart(
+            &on_progress,
+            &bucket,
+            &credentials,
+            id,
+            key,
+            file_path,
+            total_len,
+            part_size,
+            &token,
+        )
+        .await
+    };
+
+    state.unregister(id);
+    result
+}
+
+async fn upload_single(
+    on_progress: &Channel<ProgressPayload>,
+    bucket: &Bucket,
+    credentials: &Credentials,
+    id: u32,
+    key: &str,
+    file_path: &str,
+    token: &CancellationToken,
+) -> Result<serde_json::Value> {
+    let file = File::open(file_path).await?;
+    let action = bucket.put_object(Some(credentials), key);
+    let url = action.sign(PRESIGN_EXPIRY);
+
+    let client = reqwest::Client::new();
+    let request = client
+        .put(url)
+        .body(file_to_body(id, on_progress.clone(), file, None));
+
+    let response = tokio::select! {
+        biased;
+        _ = token.cancelled() => return Err(Error::Cancelled),
+        response = request.send() => response?,
+    };
+
+    if !response.status().is_success() {
+        return Err(Error::S3(format!(
+            "PUT {key} failed with status {}",
+            response.status()
+        )));
+    }
+
+    Ok(serde_json::json!({ "key": key }))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn upload_multipart(
+    on_progress: &Channel<ProgressPayload>,
+    bucket: &Bucket,
+    credentials: &Credentials,
+    id: u32,
+    key: &str,
+    file_path: &str,
+    total_len: u64,
+    part_size: u64,
+    token: &CancellationToken,
+) -> Result<serde_json::Value> {
+    let client = reqwest::Client::new();
+
+    let create_action = bucket.create_multipart_upload(Some(credentials), key);
+    let create_url = create_action.sign(PRESIGN_EXPIRY);
+    let create_body = client.post(create_url).send().await?.text().await?;
+    let multipart = rusty_s3::actions::CreateMultipartUpload::parse_response(&create_body)
+        .map_err(|err| Error::S3(err.to_string()))?;
+    let upload_id = multipart.upload_id();
+
+    match upload_parts(
+        on_progress, &client, bucket, credentials, id, key, file_path, total_len, part_size,
+        upload_id, token,
+    )
+    .await
+    {
+        Ok(etags) => {
+            let complete_action = bucket.complete_multipart_upload(
+                Some(credentials),
+                key,
+                upload_id,
+                etags.iter().map(String::as_str),
+            );
+            let complete_url = complete_action.sign(PRESIGN_EXPIRY);
+            let body = complete_action.body();
+            let response = client.post(complete_url).body(body).send().await?;
+            if !response.status().is_success() {
+                return Err(Error::S3(format!(
+                    "CompleteMultipartUpload for {key} failed with status {}",
+                    response.status()
+                )));
+            }
+            Ok(serde_json::json!({ "key": key, "parts": etags.len() }))
+        }
+        Err(err) => {
+            let abort_action = bucket.abort_multipart_upload(Some(credentials), key, upload_id);
+            let abort_url = abort_action.sign(PRESIGN_EXPIRY);
+            let _ = client.delete(abort_url).send().await;
+            Err(err)
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn upload_parts(
+    on_progress: &Channel<ProgressPayload>,
+    client: &reqwest::Client,
+    bucket: &Bucket,
+    credentials: &Credentials,
+    id: u32,
+    key: &str,
+    file_path: &str,
+    total_len: u64,
+    part_size: u64,
+    upload_id: &str,
+    token: &CancellationToken,
+) -> Result<Vec<String>> {
+    let num_parts = total_len.div_ceil(part_size);
+    if num_parts > MAX_PARTS {
+        return Err(Error::S3(format!(
+            "{file_path} needs {num_parts} parts at part_size={part_size}, \
+             which exceeds S3's {MAX_PARTS}-part limit; raise part_size"
+        )));
+    }
+    let mut etags = Vec::with_capacity(num_parts as usize);
+
+    for part_number in 1..=num_parts as u16 {
+        if token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let offset = (part_number as u64 - 1) * part_size;
+        let this_part_size = part_size.min(total_len - offset);
+        let mut file = File::open(file_path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let part_body = file_to_body(id, on_progress.clone(), file.take(this_part_size), None);
+
+        let action = bucket.upload_part(Some(credentials), key, part_number, upload_id);
+        let url = action.sign(PRESIGN_EXPIRY);
+
+        let request = client.put(url).body(part_body);
+        let response = tokio::select! {
+            biased;
+            _ = token.cancelled() => return Err(Error::Cancelled),
+            response = request.send() => response?,
+        };
+        if !response.status().is_success() {
+            return Err(Error::S3(format!(
+                "UploadPart {part_number} for {key} failed with status {}",
+                response.status()
+            )));
+        }
+        let etag = response
+            .headers()
+            .get("ETag")
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| Error::S3(format!("UploadPart {part_number} missing ETag")))?
+            .to_string();
+        etags.push(etag);
+    }
+
+    Ok(etags)
+}